@@ -0,0 +1,87 @@
+//! Wire-encoding abstraction for handler payloads. [`Json`] is the default codec and keeps
+//! existing TS clients unaffected; [`MessagePack`] encodes payloads as compact binary instead of
+//! JSON text. Since the underlying JSON-RPC envelope stays JSON either way, a non-JSON codec's
+//! bytes are carried base64-encoded inside it (see `EncodedPayload` in `lib.rs`), which adds back
+//! roughly a third in size on top of quoting — for small numeric payloads that overhead can
+//! outweigh MessagePack's own savings over encoding the value as JSON directly, so the win shows
+//! up mainly on larger or more complex payloads.
+
+use std::fmt;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+#[derive(Debug)]
+pub struct CodecError(String);
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "codec error: {}", self.0)
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+/// A wire encoding for handler request/response and subscription payloads. Handlers stay
+/// `serde`-typed regardless of which codec is chosen; only the (de)serialization at the
+/// transport boundary changes.
+pub trait Codec: Send + Sync + 'static {
+    /// Name exposed to the generated TS client so it decodes responses with the matching codec.
+    const NAME: &'static str;
+
+    fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, CodecError>;
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, CodecError>;
+}
+
+/// The default codec; unchanged from the crate's original JSON-only behaviour.
+pub struct Json;
+
+impl Codec for Json {
+    const NAME: &'static str = "json";
+
+    fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, CodecError> {
+        serde_json::to_vec(value).map_err(|error| CodecError(error.to_string()))
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, CodecError> {
+        serde_json::from_slice(bytes).map_err(|error| CodecError(error.to_string()))
+    }
+}
+
+/// A compact binary codec for high-frequency numeric subscription payloads.
+pub struct MessagePack;
+
+impl Codec for MessagePack {
+    const NAME: &'static str = "msgpack";
+
+    fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, CodecError> {
+        rmp_serde::to_vec(value).map_err(|error| CodecError(error.to_string()))
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, CodecError> {
+        rmp_serde::from_slice(bytes).map_err(|error| CodecError(error.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn json_round_trips() {
+        let encoded = Json::encode(&42u32).unwrap();
+        assert_eq!(Json::decode::<u32>(&encoded).unwrap(), 42);
+    }
+
+    #[test]
+    fn msgpack_round_trips() {
+        let encoded = MessagePack::encode(&42u32).unwrap();
+        assert_eq!(MessagePack::decode::<u32>(&encoded).unwrap(), 42);
+    }
+
+    #[test]
+    fn codec_names_are_distinct() {
+        assert_eq!(Json::NAME, "json");
+        assert_eq!(MessagePack::NAME, "msgpack");
+    }
+}
@@ -0,0 +1,315 @@
+//! A small query language for filtering subscription events, modeled on Tendermint's event
+//! query language: a conjunction of `key op value` conditions evaluated against a dotted path
+//! into a `serde_json::Value`.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde_json::Value;
+
+/// A typed literal on the right-hand side of a [`Condition`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    String(String),
+    Number(f64),
+    Bool(bool),
+}
+
+/// The comparison a [`Condition`] applies between a looked-up value and its [`Operand`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operation {
+    Eq(Operand),
+    Lt(Operand),
+    Lte(Operand),
+    Gt(Operand),
+    Gte(Operand),
+    Contains(Operand),
+    Exists,
+}
+
+/// A single `key op value` clause of a [`Query`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    pub key: String,
+    pub op: Operation,
+}
+
+/// A parsed subscription filter: a conjunction ("AND") of [`Condition`]s. An empty query matches
+/// everything.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Query(Vec<Condition>);
+
+impl Query {
+    /// Returns whether every condition in the query holds against `value`.
+    pub fn matches(&self, value: &Value) -> bool {
+        self.0.iter().all(|condition| condition.matches(value))
+    }
+}
+
+#[derive(Debug)]
+pub enum QueryParseError {
+    MalformedCondition(String),
+}
+
+impl fmt::Display for QueryParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueryParseError::MalformedCondition(clause) => {
+                write!(f, "malformed filter condition: `{clause}`")
+            }
+        }
+    }
+}
+
+impl std::error::Error for QueryParseError {}
+
+impl FromStr for Query {
+    type Err = QueryParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        if input.trim().is_empty() {
+            return Ok(Query(Vec::new()));
+        }
+
+        split_conditions(input)
+            .into_iter()
+            .map(|clause| parse_condition(clause.trim()))
+            .collect::<Result<Vec<_>, _>>()
+            .map(Query)
+    }
+}
+
+/// Splits `input` on the `AND` keyword, ignoring any `AND` that falls inside a `'...'` operand
+/// (so a quoted value like `'BRANDON'` isn't mistaken for a conjunction) and requiring the
+/// keyword to be its own whitespace-delimited token (so e.g. `BRANDON` outside quotes isn't
+/// split either). Walks `char_indices()` rather than raw bytes so a multi-byte character (e.g.
+/// in an unquoted non-ASCII key or value) never gets sliced mid-character.
+fn split_conditions(input: &str) -> Vec<&str> {
+    let mut conditions = Vec::new();
+    let mut in_quote = false;
+    let mut start = 0;
+    let mut chars = input.char_indices().peekable();
+    let mut prev_is_boundary = true;
+
+    while let Some((i, ch)) = chars.next() {
+        if ch == '\'' {
+            in_quote = !in_quote;
+            prev_is_boundary = false;
+            continue;
+        }
+
+        if !in_quote && ch == 'A' && prev_is_boundary && input[i..].starts_with("AND") {
+            let after_is_boundary = input[i + 3..]
+                .chars()
+                .next()
+                .is_none_or(char::is_whitespace);
+
+            if after_is_boundary {
+                conditions.push(&input[start..i]);
+                chars.next();
+                chars.next();
+                start = i + 3;
+                prev_is_boundary = false;
+                continue;
+            }
+        }
+
+        prev_is_boundary = ch.is_whitespace();
+    }
+
+    conditions.push(&input[start..]);
+    conditions
+}
+
+impl Condition {
+    fn matches(&self, value: &Value) -> bool {
+        let found = lookup(value, &self.key);
+
+        match &self.op {
+            Operation::Exists => found.is_some(),
+            Operation::Eq(operand) => found.is_some_and(|found| eq(found, operand)),
+            Operation::Contains(operand) => found
+                .and_then(Value::as_str)
+                .zip(as_str(operand))
+                .is_some_and(|(found, operand)| found.contains(operand)),
+            Operation::Lt(operand) => compare(found, operand, |found, operand| found < operand),
+            Operation::Lte(operand) => compare(found, operand, |found, operand| found <= operand),
+            Operation::Gt(operand) => compare(found, operand, |found, operand| found > operand),
+            Operation::Gte(operand) => compare(found, operand, |found, operand| found >= operand),
+        }
+    }
+}
+
+fn lookup<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.').try_fold(value, |current, segment| current.get(segment))
+}
+
+fn eq(found: &Value, operand: &Operand) -> bool {
+    match operand {
+        Operand::String(operand) => found.as_str() == Some(operand.as_str()),
+        Operand::Number(operand) => found.as_f64() == Some(*operand),
+        Operand::Bool(operand) => found.as_bool() == Some(*operand),
+    }
+}
+
+fn compare(found: Option<&Value>, operand: &Operand, op: impl Fn(f64, f64) -> bool) -> bool {
+    found
+        .and_then(Value::as_f64)
+        .zip(as_number(operand))
+        .is_some_and(|(found, operand)| op(found, operand))
+}
+
+fn as_str(operand: &Operand) -> Option<&str> {
+    match operand {
+        Operand::String(operand) => Some(operand),
+        _ => None,
+    }
+}
+
+fn as_number(operand: &Operand) -> Option<f64> {
+    match operand {
+        Operand::Number(operand) => Some(*operand),
+        _ => None,
+    }
+}
+
+fn parse_condition(clause: &str) -> Result<Condition, QueryParseError> {
+    if let Some(key) = clause.strip_suffix("EXISTS").map(str::trim) {
+        if key.is_empty() {
+            return Err(QueryParseError::MalformedCondition(clause.to_string()));
+        }
+
+        return Ok(Condition {
+            key: key.to_string(),
+            op: Operation::Exists,
+        });
+    }
+
+    const OPERATORS: &[(&str, fn(Operand) -> Operation)] = &[
+        ("<=", Operation::Lte),
+        (">=", Operation::Gte),
+        ("CONTAINS", Operation::Contains),
+        ("=", Operation::Eq),
+        ("<", Operation::Lt),
+        (">", Operation::Gt),
+    ];
+
+    for (token, build) in OPERATORS {
+        let Some((key, value)) = clause.split_once(token) else {
+            continue;
+        };
+
+        let key = key.trim();
+        let value = value.trim();
+        if key.is_empty() || value.is_empty() {
+            continue;
+        }
+
+        return Ok(Condition {
+            key: key.to_string(),
+            op: build(parse_operand(value)),
+        });
+    }
+
+    Err(QueryParseError::MalformedCondition(clause.to_string()))
+}
+
+fn parse_operand(value: &str) -> Operand {
+    if let Some(quoted) = value
+        .strip_prefix('\'')
+        .and_then(|value| value.strip_suffix('\''))
+    {
+        return Operand::String(quoted.to_string());
+    }
+
+    if let Ok(number) = value.parse::<f64>() {
+        return Operand::Number(number);
+    }
+
+    if let Ok(boolean) = value.parse::<bool>() {
+        return Operand::Bool(boolean);
+    }
+
+    Operand::String(value.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn empty_query_matches_everything() {
+        let query: Query = "".parse().unwrap();
+        assert!(query.matches(&json!({})));
+    }
+
+    #[test]
+    fn eq_condition_matches_string() {
+        let query: Query = "kind = 'transfer'".parse().unwrap();
+        assert!(query.matches(&json!({ "kind": "transfer" })));
+        assert!(!query.matches(&json!({ "kind": "mint" })));
+    }
+
+    #[test]
+    fn numeric_comparison() {
+        let query: Query = "amount > 10".parse().unwrap();
+        assert!(query.matches(&json!({ "amount": 11 })));
+        assert!(!query.matches(&json!({ "amount": 10 })));
+    }
+
+    #[test]
+    fn exists_condition() {
+        let query: Query = "metadata.memo EXISTS".parse().unwrap();
+        assert!(query.matches(&json!({ "metadata": { "memo": "hi" } })));
+        assert!(!query.matches(&json!({ "metadata": {} })));
+    }
+
+    #[test]
+    fn contains_condition_is_substring_match() {
+        let query: Query = "memo CONTAINS 'hello'".parse().unwrap();
+        assert!(query.matches(&json!({ "memo": "oh hello there" })));
+        assert!(!query.matches(&json!({ "memo": "goodbye" })));
+    }
+
+    #[test]
+    fn conjunction_requires_all_conditions() {
+        let query: Query = "kind = 'transfer' AND amount >= 100".parse().unwrap();
+        assert!(query.matches(&json!({ "kind": "transfer", "amount": 100 })));
+        assert!(!query.matches(&json!({ "kind": "transfer", "amount": 99 })));
+    }
+
+    #[test]
+    fn dotted_path_looks_up_nested_keys() {
+        let query: Query = "sender.address = 'abc'".parse().unwrap();
+        assert!(query.matches(&json!({ "sender": { "address": "abc" } })));
+    }
+
+    #[test]
+    fn malformed_query_is_rejected() {
+        assert!("not a query".parse::<Query>().is_err());
+    }
+
+    #[test]
+    fn quoted_operand_containing_and_is_not_split() {
+        let query: Query = "name = 'BRANDON'".parse().unwrap();
+        assert!(query.matches(&json!({ "name": "BRANDON" })));
+        assert!(!query.matches(&json!({ "name": "someone else" })));
+    }
+
+    #[test]
+    fn conjunction_still_splits_around_a_quoted_and() {
+        let query: Query = "name = 'BRANDON' AND amount > 1".parse().unwrap();
+        assert!(query.matches(&json!({ "name": "BRANDON", "amount": 2 })));
+        assert!(!query.matches(&json!({ "name": "BRANDON", "amount": 0 })));
+    }
+
+    #[test]
+    fn non_ascii_operand_does_not_panic() {
+        let query: Query = "name = 'café'".parse().unwrap();
+        assert!(query.matches(&json!({ "name": "café" })));
+
+        let query: Query = "name = café AND amount > 1".parse().unwrap();
+        assert!(query.matches(&json!({ "name": "café", "amount": 2 })));
+    }
+}
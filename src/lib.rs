@@ -1,42 +1,132 @@
 use std::collections::HashSet;
+use std::marker::PhantomData;
+use std::sync::Arc;
 
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use futures::{Future, FutureExt, Stream, StreamExt};
-use jsonrpsee::{server::StopHandle, types::Params, RpcModule, SubscriptionMessage};
+use jsonrpsee::{
+    server::StopHandle,
+    types::{ErrorCode, ErrorObjectOwned, Params},
+    RpcModule, SubscriptionMessage,
+};
 pub use rs_ts_api_macros::*;
+use codec::{Codec, Json};
+use filter::Query;
+use serde::{Deserialize, Serialize, Serializer};
 use server::ServerService;
 use ts_rs::Dependency;
 
+pub mod codec;
+pub mod filter;
+pub mod mqtt;
 pub mod server;
 
+/// JSON-RPC error code used for application errors returned from a handler's `Result::Err`,
+/// taken from the "server error" range reserved by the spec (-32000 to -32099).
+const HANDLER_ERROR_CODE: i32 = -32000;
+
+/// Wraps a handler's codec-encoded success value so it can be handed to jsonrpsee as a
+/// `Serialize` response payload. JSON-encoded bytes are re-emitted verbatim (they're already
+/// valid JSON); any other codec is carried as a base64 string so the JSON-RPC envelope itself
+/// stays valid JSON regardless of the payload codec.
+struct EncodedPayload<C> {
+    bytes: Vec<u8>,
+    _codec: PhantomData<C>,
+}
+
+impl<C: Codec> EncodedPayload<C> {
+    fn new(bytes: Vec<u8>) -> Self {
+        Self {
+            bytes,
+            _codec: PhantomData,
+        }
+    }
+}
+
+impl<C: Codec> Serialize for EncodedPayload<C> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if C::NAME == Json::NAME {
+            let raw = serde_json::value::RawValue::from_string(
+                String::from_utf8(self.bytes.clone()).map_err(serde::ser::Error::custom)?,
+            )
+            .map_err(serde::ser::Error::custom)?;
+
+            raw.serialize(serializer)
+        } else {
+            serializer.serialize_str(&BASE64.encode(&self.bytes))
+        }
+    }
+}
+
+/// The shape of the reserved `filter` subscription parameter, deserialized up front so the
+/// forwarding loop can apply it independently of whatever params the handler itself expects.
+/// `filter` is kept as a raw [`serde_json::Value`] (rather than `Option<String>`) so a `filter`
+/// field present with the wrong JSON type can be told apart from no `filter` field at all and
+/// rejected, instead of silently falling back to unfiltered.
+#[derive(Deserialize, Default)]
+struct SubscriptionFilterParam {
+    #[serde(default)]
+    filter: Option<serde_json::Value>,
+}
+
 pub struct HandlerType {
     pub name: String,
     pub signature: String,
+    pub kind: HandlerKind,
+    /// The TS type of the error a handler's `Result::Err` resolves to, if any.
+    pub error: Option<String>,
     pub dependencies: Vec<Dependency>,
 }
 
-pub trait Handler {
-    fn register(rpc_builder: RpcBuilder) -> RpcBuilder;
+/// Distinguishes a request/response handler from a long-lived subscription, since the
+/// generated client calls each of them very differently.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum HandlerKind {
+    Query,
+    Subscription,
+}
+
+pub trait Handler<Ctx> {
+    /// Generic over the codec so the same handler registers unchanged regardless of which
+    /// codec the enclosing `Router` was built with.
+    fn register<C: Codec>(rpc_builder: RpcBuilder<Ctx, C>) -> RpcBuilder<Ctx, C>;
 
     fn get_type() -> HandlerType;
 }
 
-pub struct RpcBuilder(RpcModule<()>);
-impl RpcBuilder {
-    pub fn new() -> Self {
-        Self(RpcModule::new(()))
+pub struct RpcBuilder<Ctx, C: Codec = Json>(RpcModule<Ctx>, PhantomData<C>);
+impl<Ctx: Send + Sync + 'static, C: Codec> RpcBuilder<Ctx, C> {
+    pub fn new(ctx: Ctx) -> Self {
+        Self(RpcModule::new(ctx), PhantomData)
     }
 
-    pub fn query<F, Fut>(mut self, name: &'static str, handler: F) -> Self
+    pub fn query<F, Fut, T, E>(mut self, name: &'static str, handler: F) -> Self
     where
-        F: Fn(Params<'static>) -> Fut + Send + Sync + Clone + 'static,
-        Fut: Future<Output = serde_json::Value> + Send + 'static,
+        F: Fn(Arc<Ctx>, Params<'static>) -> Fut + Send + Sync + Clone + 'static,
+        Fut: Future<Output = Result<T, E>> + Send + 'static,
+        T: Serialize + 'static,
+        E: Serialize + 'static,
     {
         self.0
-            .register_async_method(name, move |params, _ctx| {
+            .register_async_method(name, move |params, ctx| {
                 let handler = handler.clone();
 
                 async move {
-                    handler(params).await;
+                    let value = handler(ctx, params).await.map_err(|error| {
+                        ErrorObjectOwned::owned(
+                            ErrorCode::ServerError(HANDLER_ERROR_CODE).code(),
+                            "handler returned an error",
+                            serde_json::to_value(error).ok(),
+                        )
+                    })?;
+
+                    C::encode(&value).map(EncodedPayload::<C>::new).map_err(|error| {
+                        ErrorObjectOwned::owned(
+                            ErrorCode::InternalError.code(),
+                            error.to_string(),
+                            None::<()>,
+                        )
+                    })
                 }
             })
             .unwrap();
@@ -52,7 +142,7 @@ impl RpcBuilder {
         handler: F,
     ) -> Self
     where
-        F: Fn(Params<'static>) -> S + Send + Sync + Clone + 'static,
+        F: Fn(Arc<Ctx>, Params<'static>) -> S + Send + Sync + Clone + 'static,
         S: Stream<Item = serde_json::Value> + Send + 'static,
     {
         self.0
@@ -60,32 +150,94 @@ impl RpcBuilder {
                 name,
                 notification_name,
                 unsubscribe_name,
-                move |params, subscription, _ctx| {
+                move |params, subscription, ctx| {
                     let handler = handler.clone();
 
                     async move {
+                        // An optional `filter` parameter selects a query language filter (see
+                        // the `filter` module) to apply to the handler's stream. Params that
+                        // don't carry a `filter` field at all (e.g. positional params entirely
+                        // consumed by the handler) are treated as unfiltered; a `filter` field
+                        // that isn't a string, or a string that fails to parse, rejects the
+                        // subscription outright rather than silently subscribing unfiltered.
+                        let filter = params
+                            .clone()
+                            .parse::<SubscriptionFilterParam>()
+                            .ok()
+                            .and_then(|param| param.filter);
+
+                        let query = match filter {
+                            None => None,
+                            Some(serde_json::Value::String(filter)) => {
+                                match filter.parse::<Query>() {
+                                    Ok(query) => Some(query),
+                                    Err(error) => {
+                                        let _ = subscription
+                                            .reject(ErrorObjectOwned::owned(
+                                                ErrorCode::InvalidParams.code(),
+                                                error.to_string(),
+                                                None::<()>,
+                                            ))
+                                            .await;
+                                        return;
+                                    }
+                                }
+                            }
+                            Some(_) => {
+                                let _ = subscription
+                                    .reject(ErrorObjectOwned::owned(
+                                        ErrorCode::InvalidParams.code(),
+                                        "`filter` must be a string",
+                                        None::<()>,
+                                    ))
+                                    .await;
+                                return;
+                            }
+                        };
+
                         // Accept the subscription
-                        let subscription = subscription.accept().await.unwrap();
+                        let Ok(subscription) = subscription.accept().await else {
+                            return;
+                        };
 
                         // Set up a channel to avoid cloning the subscription
                         let (tx, mut rx) = tokio::sync::mpsc::channel(10);
 
-                        // Recieve values on a new thread, sending them onwards to the subscription
+                        // Recieve values on a new thread, sending them onwards to the subscription.
+                        // A serialization or send failure closes the subscription rather than
+                        // aborting the task.
                         tokio::spawn(async move {
                             while let Some(value) = rx.recv().await {
-                                subscription
-                                    .send(SubscriptionMessage::from_json(&value).unwrap())
-                                    .await
-                                    .unwrap();
+                                let Ok(bytes) = C::encode(&value) else {
+                                    break;
+                                };
+
+                                let Ok(message) =
+                                    SubscriptionMessage::from_json(&EncodedPayload::<C>::new(bytes))
+                                else {
+                                    break;
+                                };
+
+                                if subscription.send(message).await.is_err() {
+                                    break;
+                                }
                             }
-                        })
-                        .await
-                        .unwrap();
-
-                        // Run the handler, capturing each of the values sand forwarding it onwards
-                        // to the channel
-                        handler(params)
-                            .for_each(|value| tx.send(value).map(|result| result.unwrap()))
+                        });
+
+                        // Run the handler, filtering each value through the query (if any)
+                        // before forwarding it onwards to the channel
+                        handler(ctx, params)
+                            .filter(move |value| {
+                                let matches =
+                                    query.as_ref().map_or(true, |query| query.matches(value));
+                                async move { matches }
+                            })
+                            .for_each(|value| {
+                                let tx = tx.clone();
+                                async move {
+                                    let _ = tx.send(value).await;
+                                }
+                            })
                             .await;
                     }
                 },
@@ -96,30 +248,30 @@ impl RpcBuilder {
     }
 }
 
-pub struct Router {
+pub struct Router<Ctx, C: Codec = Json> {
     name: Option<String>,
     handlers: Vec<fn() -> HandlerType>,
-    rpc_builder: RpcBuilder,
+    rpc_builder: RpcBuilder<Ctx, C>,
 }
 
-impl Router {
-    pub fn new() -> Self {
+impl<Ctx: Send + Sync + 'static, C: Codec> Router<Ctx, C> {
+    pub fn new(ctx: Ctx) -> Self {
         Self {
             name: None,
             handlers: Vec::new(),
-            rpc_builder: RpcBuilder::new(),
+            rpc_builder: RpcBuilder::new(ctx),
         }
     }
 
-    pub fn namespace(name: impl ToString) -> Self {
+    pub fn namespace(name: impl ToString, ctx: Ctx) -> Self {
         Self {
             name: Some(name.to_string()),
             handlers: Vec::new(),
-            rpc_builder: RpcBuilder::new(),
+            rpc_builder: RpcBuilder::new(ctx),
         }
     }
 
-    pub fn handler<H: Handler>(mut self, _: H) -> Self {
+    pub fn handler<H: Handler<Ctx>>(mut self, _: H) -> Self {
         self.rpc_builder = H::register(self.rpc_builder);
         self.handlers.push(H::get_type);
 
@@ -132,8 +284,17 @@ impl Router {
             .iter()
             .map(|get_type| get_type())
             .map(|handler_type| {
+                let error_comment = handler_type
+                    .error
+                    .as_ref()
+                    .map(|error| format!(" /* throws: {error} */"))
+                    .unwrap_or_default();
+
                 (
-                    format!("{}: {}", handler_type.name, handler_type.signature),
+                    format!(
+                        "{}: {}{}",
+                        handler_type.name, handler_type.signature, error_comment
+                    ),
                     handler_type.dependencies,
                 )
             })
@@ -164,6 +325,47 @@ impl Router {
         format!("{}\ntype Router = {router_type};", dependencies.join("\n"))
     }
 
+    /// Generate a runtime TypeScript client that mirrors the `Router` type from [`Router::get_type`]:
+    /// a plain object whose methods issue the underlying JSON-RPC calls over a pluggable
+    /// `Transport`, rather than leaving callers to hand-write that plumbing.
+    pub fn generate_client(&self) -> String {
+        let client_entries = self
+            .handlers
+            .iter()
+            .map(|get_type| get_type())
+            .map(|handler_type| {
+                let handler_ref = match &self.name {
+                    Some(namespace) => format!("Router[\"{namespace}\"][\"{}\"]", handler_type.name),
+                    None => format!("Router[\"{}\"]", handler_type.name),
+                };
+
+                let call = match handler_type.kind {
+                    HandlerKind::Query => format!(
+                        "(...args: Parameters<{handler_ref}>) => transport.query(\"{name}\", args) as ReturnType<{handler_ref}>",
+                        name = handler_type.name,
+                    ),
+                    HandlerKind::Subscription => format!(
+                        "(...args: [...Parameters<{handler_ref}>, filter?: string]) => transport.subscribe(\"{name}\", args) as ReturnType<{handler_ref}>",
+                        name = handler_type.name,
+                    ),
+                };
+
+                format!("{}: {}", handler_type.name, call)
+            })
+            .collect::<Vec<_>>();
+
+        let mut client = format!("{{ {} }}", client_entries.join(", "));
+
+        if let Some(name) = &self.name {
+            client = format!("{{ {name}: {client} }}");
+        }
+
+        format!(
+            "export interface Transport {{\n  readonly codec: \"{codec}\";\n  query(name: string, args: unknown[]): Promise<unknown>;\n  subscribe(name: string, args: unknown[]): AsyncIterable<unknown>;\n}}\nexport const createClient = (transport: Transport): Router => ({client});",
+            codec = C::NAME,
+        )
+    }
+
     pub fn create_service(self, stop_handle: StopHandle) -> ServerService {
         let svc_builder = jsonrpsee::server::Server::builder().to_service_builder();
 
@@ -171,6 +373,21 @@ impl Router {
             service: svc_builder.build(self.rpc_builder.0, stop_handle),
         }
     }
+
+    /// Expose the registered handlers over MQTT instead of (or alongside) the HTTP/WS service
+    /// from [`Router::create_service`]. Queries are answered request/response style on a
+    /// correlation topic; subscriptions republish each streamed value onto a per-subscription
+    /// topic, and their handler stream is torn down once the last subscriber sends an unsubscribe
+    /// request (see the `mqtt` module); a subscriber that only drops its broker connection does
+    /// not count as leaving.
+    pub async fn create_mqtt_bridge(self, mqtt_options: rumqttc::MqttOptions) -> mqtt::MqttBridge {
+        let (client, event_loop) = rumqttc::AsyncClient::new(mqtt_options, 64);
+        let module = Arc::new(self.rpc_builder.0);
+
+        tokio::spawn(mqtt::run(module, client.clone(), event_loop, self.handlers));
+
+        mqtt::MqttBridge { client }
+    }
 }
 
 #[cfg(test)]
@@ -179,8 +396,8 @@ mod test {
 
     #[allow(non_camel_case_types)]
     struct sample_handler;
-    impl Handler for sample_handler {
-        fn register(_rpc_builder: RpcBuilder) -> RpcBuilder {
+    impl Handler<()> for sample_handler {
+        fn register<C: Codec>(_rpc_builder: RpcBuilder<(), C>) -> RpcBuilder<(), C> {
             todo!()
         }
 
@@ -188,6 +405,8 @@ mod test {
             HandlerType {
                 name: "sample_handler".to_string(),
                 signature: "() => void".to_string(),
+                kind: HandlerKind::Query,
+                error: None,
                 dependencies: Vec::new(),
             }
         }
@@ -195,8 +414,8 @@ mod test {
 
     #[allow(non_camel_case_types)]
     struct another_handler;
-    impl Handler for another_handler {
-        fn register(_rpc_builder: RpcBuilder) -> RpcBuilder {
+    impl Handler<()> for another_handler {
+        fn register<C: Codec>(_rpc_builder: RpcBuilder<(), C>) -> RpcBuilder<(), C> {
             todo!()
         }
 
@@ -204,6 +423,8 @@ mod test {
             HandlerType {
                 name: "another_handler".to_string(),
                 signature: "() => number".to_string(),
+                kind: HandlerKind::Query,
+                error: None,
                 dependencies: Vec::new(),
             }
         }
@@ -211,31 +432,31 @@ mod test {
 
     #[test]
     fn empty_router() {
-        let router = Router::new();
+        let router = Router::new(());
         assert_eq!(router.get_type(), "{  }");
     }
 
     #[test]
     fn namespaced_empty_router() {
-        let router = Router::namespace("ns");
+        let router = Router::namespace("ns", ());
         assert_eq!(router.get_type(), "{ ns: {  } }");
     }
 
     #[test]
     fn single_handler() {
-        let router = Router::new().handler(sample_handler);
+        let router = Router::new(()).handler(sample_handler);
         assert_eq!(router.get_type(), "{ sample_handler: () => void }");
     }
 
     #[test]
     fn namespaced_single_handler() {
-        let router = Router::namespace("ns").handler(sample_handler);
+        let router = Router::namespace("ns", ()).handler(sample_handler);
         assert_eq!(router.get_type(), "{ ns: { sample_handler: () => void } }");
     }
 
     #[test]
     fn multiple_handlers() {
-        let router = Router::new()
+        let router = Router::new(())
             .handler(sample_handler)
             .handler(another_handler);
         assert_eq!(
@@ -246,7 +467,7 @@ mod test {
 
     #[test]
     fn namespaced_multiple_handlers() {
-        let router = Router::namespace("ns")
+        let router = Router::namespace("ns", ())
             .handler(sample_handler)
             .handler(another_handler);
         assert_eq!(
@@ -254,4 +475,97 @@ mod test {
             "{ ns: { sample_handler: () => void, another_handler: () => void } }"
         );
     }
+
+    #[test]
+    fn client_calls_queries_through_transport() {
+        let router = Router::new(()).handler(sample_handler);
+        assert!(router
+            .generate_client()
+            .contains("sample_handler: (...args: Parameters<Router[\"sample_handler\"]>) => transport.query(\"sample_handler\", args)"));
+    }
+
+    #[test]
+    fn namespaced_client_nests_under_namespace() {
+        let router = Router::namespace("ns", ()).handler(sample_handler);
+        assert!(router
+            .generate_client()
+            .contains("export const createClient = (transport: Transport): Router => ({ ns: { sample_handler:"));
+    }
+
+    #[test]
+    fn namespaced_client_indexes_through_the_namespace() {
+        let router = Router::namespace("ns", ()).handler(sample_handler);
+        assert!(router.generate_client().contains(
+            "sample_handler: (...args: Parameters<Router[\"ns\"][\"sample_handler\"]>) => transport.query(\"sample_handler\", args) as ReturnType<Router[\"ns\"][\"sample_handler\"]>"
+        ));
+    }
+
+    #[allow(non_camel_case_types)]
+    struct fallible_handler;
+    impl Handler<()> for fallible_handler {
+        fn register<C: Codec>(_rpc_builder: RpcBuilder<(), C>) -> RpcBuilder<(), C> {
+            todo!()
+        }
+
+        fn get_type() -> HandlerType {
+            HandlerType {
+                name: "fallible_handler".to_string(),
+                signature: "() => Promise<void>".to_string(),
+                kind: HandlerKind::Query,
+                error: Some("MyError".to_string()),
+                dependencies: Vec::new(),
+            }
+        }
+    }
+
+    #[test]
+    fn error_type_is_reflected_as_a_throws_comment() {
+        let router = Router::new(()).handler(fallible_handler);
+        assert_eq!(
+            router.get_type(),
+            "\ntype Router = { fallible_handler: () => Promise<void> /* throws: MyError */ };"
+        );
+    }
+
+    #[allow(non_camel_case_types)]
+    struct subscription_handler;
+    impl Handler<()> for subscription_handler {
+        fn register<C: Codec>(_rpc_builder: RpcBuilder<(), C>) -> RpcBuilder<(), C> {
+            todo!()
+        }
+
+        fn get_type() -> HandlerType {
+            HandlerType {
+                name: "subscription_handler".to_string(),
+                signature: "() => AsyncIterable<number>".to_string(),
+                kind: HandlerKind::Subscription,
+                error: None,
+                dependencies: Vec::new(),
+            }
+        }
+    }
+
+    #[test]
+    fn client_accepts_an_optional_filter_for_subscriptions() {
+        let router = Router::new(()).handler(subscription_handler);
+        assert!(router.generate_client().contains(
+            "subscription_handler: (...args: [...Parameters<Router[\"subscription_handler\"]>, filter?: string]) => transport.subscribe(\"subscription_handler\", args)"
+        ));
+    }
+
+    #[test]
+    fn client_exposes_json_codec_by_default() {
+        let router = Router::new(()).handler(sample_handler);
+        assert!(router
+            .generate_client()
+            .contains("readonly codec: \"json\";"));
+    }
+
+    #[test]
+    fn client_exposes_the_chosen_codec() {
+        let router = Router::<(), crate::codec::MessagePack>::new(()).handler(sample_handler);
+        assert!(router
+            .generate_client()
+            .contains("readonly codec: \"msgpack\";"));
+    }
 }
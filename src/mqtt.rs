@@ -0,0 +1,456 @@
+//! An MQTT bridge that exposes the same handlers registered with a [`Router`](crate::Router)
+//! over MQTT, following the broker-bridge pattern: queries are request/response over a
+//! correlation topic, subscriptions republish each streamed value onto a per-subscription topic.
+//!
+//! Plain MQTT gives a client no visibility into *other* clients subscribing to or leaving a
+//! topic (that bookkeeping lives in the broker), so subscribing and unsubscribing are modelled
+//! as explicit application-level requests, each naming the requesting `client_id`: publishing to
+//! `{topic}/subscribe` joins it to the handler's stream, and `{topic}/unsubscribe` leaves it. The
+//! handler's stream is only started on the first subscriber and torn down once the last one
+//! leaves.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use jsonrpsee::types::{ErrorCode, ErrorObjectOwned};
+use rumqttc::{AsyncClient, Event, Packet, QoS};
+use serde::Deserialize;
+use tokio::sync::{Mutex, Notify};
+
+use crate::{HandlerKind, HandlerType};
+
+const QUERY_TOPIC_PREFIX: &str = "qubit/query";
+const QUERY_RESPONSE_TOPIC_PREFIX: &str = "qubit/query/response";
+const SUBSCRIPTION_TOPIC_PREFIX: &str = "qubit/subscription";
+
+/// A handle to a running MQTT bridge. The bridge's event loop runs on a background task that
+/// holds its own clone of the client, so dropping this handle does not stop it; the task ends
+/// once polling the MQTT connection returns an error, or when the process exits.
+pub struct MqttBridge {
+    pub(crate) client: AsyncClient,
+}
+
+impl MqttBridge {
+    /// Access the underlying MQTT client, e.g. to publish additional application topics.
+    pub fn client(&self) -> &AsyncClient {
+        &self.client
+    }
+}
+
+/// The subscribers currently attached to one handler's live stream, and the `Notify` used to
+/// wake that stream's task once it might be time to tear down.
+struct TopicSubscribers {
+    client_ids: HashSet<String>,
+    notify: Arc<Notify>,
+}
+
+impl TopicSubscribers {
+    fn new(client_id: String) -> Self {
+        Self {
+            client_ids: HashSet::from([client_id]),
+            notify: Arc::new(Notify::new()),
+        }
+    }
+
+    fn add(&mut self, client_id: String) {
+        self.client_ids.insert(client_id);
+    }
+
+    /// Removes `client_id`, returning whether no subscribers remain.
+    fn remove(&mut self, client_id: &str) -> bool {
+        self.client_ids.remove(client_id);
+        self.client_ids.is_empty()
+    }
+}
+
+/// Live handler streams, keyed by handler name. When the last subscriber of a handler sends an
+/// unsubscribe request, its entry is removed and the stream torn down so idle subscriptions don't
+/// keep producing. A subscriber that drops its broker connection without unsubscribing is still
+/// counted.
+type LiveSubscriptions = Arc<Mutex<HashMap<String, TopicSubscribers>>>;
+
+#[derive(Deserialize)]
+struct QueryRequest {
+    #[serde(default)]
+    correlation_id: String,
+    #[serde(default)]
+    params: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct SubscribeRequest {
+    client_id: String,
+    #[serde(default)]
+    params: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct UnsubscribeRequest {
+    client_id: String,
+}
+
+pub(crate) async fn run<Ctx: Send + Sync + 'static>(
+    module: Arc<jsonrpsee::RpcModule<Ctx>>,
+    client: AsyncClient,
+    mut event_loop: rumqttc::EventLoop,
+    handlers: Vec<fn() -> HandlerType>,
+) {
+    let live_subscriptions: LiveSubscriptions = Default::default();
+
+    for handler_type in handlers.iter().map(|get_type| get_type()) {
+        for topic in control_topics(&handler_type) {
+            let _ = client.subscribe(topic, QoS::AtLeastOnce).await;
+        }
+    }
+
+    while let Ok(event) = event_loop.poll().await {
+        let Event::Incoming(Packet::Publish(publish)) = event else {
+            continue;
+        };
+
+        let payload = publish.payload.to_vec();
+
+        if let Some(name) = publish.topic.strip_prefix(&format!("{QUERY_TOPIC_PREFIX}/")) {
+            handle_query(&module, &client, name, payload).await;
+        } else if let Some(name) = subscription_name(&publish.topic, "/subscribe") {
+            handle_subscribe(
+                module.clone(),
+                client.clone(),
+                name,
+                payload,
+                live_subscriptions.clone(),
+            )
+            .await;
+        } else if let Some(name) = subscription_name(&publish.topic, "/unsubscribe") {
+            handle_unsubscribe(name, payload, live_subscriptions.clone()).await;
+        }
+    }
+}
+
+fn subscription_name(topic: &str, suffix: &str) -> Option<String> {
+    topic
+        .strip_suffix(suffix)?
+        .strip_prefix(&format!("{SUBSCRIPTION_TOPIC_PREFIX}/"))
+        .map(str::to_string)
+}
+
+fn control_topics(handler_type: &HandlerType) -> Vec<String> {
+    match handler_type.kind {
+        HandlerKind::Query => vec![format!("{QUERY_TOPIC_PREFIX}/{}", handler_type.name)],
+        HandlerKind::Subscription => vec![
+            format!("{SUBSCRIPTION_TOPIC_PREFIX}/{}/subscribe", handler_type.name),
+            format!(
+                "{SUBSCRIPTION_TOPIC_PREFIX}/{}/unsubscribe",
+                handler_type.name
+            ),
+        ],
+    }
+}
+
+/// Handles one query request, publishing a `{ ok: true, value }` or
+/// `{ ok: false, code, message, data }` envelope to the response topic so a caller can tell a
+/// handler error apart from a successful empty response. `data` carries the handler's typed
+/// `Result::Err` value (see [`RpcBuilder::query`](crate::RpcBuilder::query)) as JSON, not just a
+/// human-readable message, so the typed error channel from the query builder isn't lost over
+/// MQTT. When the request carries a `correlation_id`, the response is published under it so
+/// concurrent callers on the same query topic can tell their response apart from another
+/// caller's.
+async fn handle_query<Ctx: Send + Sync + 'static>(
+    module: &jsonrpsee::RpcModule<Ctx>,
+    client: &AsyncClient,
+    name: &str,
+    payload: Vec<u8>,
+) {
+    let Ok(request) = serde_json::from_slice::<QueryRequest>(&payload) else {
+        return;
+    };
+
+    let result = module.call::<_, serde_json::Value>(name, request.params).await;
+    let response = query_response_envelope(result);
+
+    let Ok(payload) = serde_json::to_vec(&response) else {
+        return;
+    };
+
+    let topic = if request.correlation_id.is_empty() {
+        format!("{QUERY_RESPONSE_TOPIC_PREFIX}/{name}")
+    } else {
+        format!(
+            "{QUERY_RESPONSE_TOPIC_PREFIX}/{name}/{}",
+            request.correlation_id
+        )
+    };
+
+    let _ = client.publish(topic, QoS::AtLeastOnce, false, payload).await;
+}
+
+/// Builds the `{ ok: true, value }` / `{ ok: false, code, message, data }` response envelope for
+/// a query call, carrying a handler error's `data` (the typed `E` from
+/// [`RpcBuilder::query`](crate::RpcBuilder::query)) through as JSON rather than collapsing it to
+/// a message string.
+fn query_response_envelope(result: Result<serde_json::Value, jsonrpsee::core::Error>) -> serde_json::Value {
+    match result {
+        Ok(value) => serde_json::json!({ "ok": true, "value": value }),
+        Err(jsonrpsee::core::Error::Call(error)) => serde_json::json!({
+            "ok": false,
+            "code": error.code(),
+            "message": error.message(),
+            "data": error
+                .data()
+                .and_then(|data| serde_json::from_str::<serde_json::Value>(data.get()).ok()),
+        }),
+        Err(error) => serde_json::json!({
+            "ok": false,
+            "code": ErrorCode::InternalError.code(),
+            "message": error.to_string(),
+            "data": null,
+        }),
+    }
+}
+
+async fn handle_subscribe<Ctx: Send + Sync + 'static>(
+    module: Arc<jsonrpsee::RpcModule<Ctx>>,
+    client: AsyncClient,
+    name: String,
+    payload: Vec<u8>,
+    live_subscriptions: LiveSubscriptions,
+) {
+    let Ok(request) = serde_json::from_slice::<SubscribeRequest>(&payload) else {
+        return;
+    };
+
+    let mut subscriptions = live_subscriptions.lock().await;
+    if let Some(subscribers) = subscriptions.get_mut(&name) {
+        // The handler's stream is already running for another subscriber; just join it.
+        subscribers.add(request.client_id);
+        return;
+    }
+
+    let subscribers = TopicSubscribers::new(request.client_id);
+    let notify = subscribers.notify.clone();
+    subscriptions.insert(name.clone(), subscribers);
+    drop(subscriptions);
+
+    let Ok(subscription) = module.subscribe(&name, request.params, 16).await else {
+        live_subscriptions.lock().await.remove(&name);
+        return;
+    };
+
+    let topic = format!("{SUBSCRIPTION_TOPIC_PREFIX}/{name}");
+    tokio::spawn(run_subscription_stream(
+        subscription,
+        client,
+        topic,
+        name,
+        live_subscriptions,
+        notify,
+    ));
+}
+
+async fn handle_unsubscribe(name: String, payload: Vec<u8>, live_subscriptions: LiveSubscriptions) {
+    let Ok(request) = serde_json::from_slice::<UnsubscribeRequest>(&payload) else {
+        return;
+    };
+
+    let mut subscriptions = live_subscriptions.lock().await;
+    let Some(subscribers) = subscriptions.get_mut(&name) else {
+        return;
+    };
+
+    if subscribers.remove(&request.client_id) {
+        let notify = subscribers.notify.clone();
+        drop(subscriptions);
+        notify.notify_one();
+    }
+}
+
+async fn run_subscription_stream(
+    mut subscription: jsonrpsee::core::client::Subscription,
+    client: AsyncClient,
+    topic: String,
+    name: String,
+    live_subscriptions: LiveSubscriptions,
+    notify: Arc<Notify>,
+) {
+    loop {
+        tokio::select! {
+            // A subscriber left; re-check under the lock since another may have joined in the
+            // meantime, and only tear down once the subscriber set is actually empty.
+            _ = notify.notified() => {
+                let mut subscriptions = live_subscriptions.lock().await;
+                let is_empty = subscriptions
+                    .get(&name)
+                    .map_or(true, |subscribers| subscribers.client_ids.is_empty());
+
+                if is_empty {
+                    subscriptions.remove(&name);
+                    break;
+                }
+            }
+            next = subscription.next::<serde_json::Value>() => {
+                let Some(Ok((value, _))) = next else {
+                    break;
+                };
+
+                let Ok(payload) = serde_json::to_vec(&value) else {
+                    continue;
+                };
+
+                if client
+                    .publish(&topic, QoS::AtLeastOnce, false, payload)
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        }
+    }
+
+    live_subscriptions.lock().await.remove(&name);
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn topic_subscribers_reports_emptiness_after_last_removal() {
+        let mut subscribers = TopicSubscribers::new("a".to_string());
+        subscribers.add("b".to_string());
+
+        assert!(!subscribers.remove("a"));
+        assert!(subscribers.remove("b"));
+    }
+
+    fn query_module() -> jsonrpsee::RpcModule<()> {
+        let mut module = jsonrpsee::RpcModule::new(());
+
+        module
+            .register_async_method("ok_query", |_params, _ctx| async move {
+                Ok::<_, ErrorObjectOwned>(serde_json::json!({ "answer": 42 }))
+            })
+            .unwrap();
+
+        module
+            .register_async_method("failing_query", |_params, _ctx| async move {
+                Err::<serde_json::Value, _>(ErrorObjectOwned::owned(
+                    ErrorCode::ServerError(-32000).code(),
+                    "handler returned an error",
+                    Some(serde_json::json!({ "reason": "not found" })),
+                ))
+            })
+            .unwrap();
+
+        module
+    }
+
+    #[tokio::test]
+    async fn query_response_envelope_carries_the_success_value() {
+        let module = query_module();
+        let result = module
+            .call::<_, serde_json::Value>("ok_query", serde_json::Value::Null)
+            .await;
+
+        assert_eq!(
+            query_response_envelope(result),
+            serde_json::json!({ "ok": true, "value": { "answer": 42 } })
+        );
+    }
+
+    #[tokio::test]
+    async fn query_response_envelope_carries_the_typed_error_data() {
+        let module = query_module();
+        let result = module
+            .call::<_, serde_json::Value>("failing_query", serde_json::Value::Null)
+            .await;
+
+        let envelope = query_response_envelope(result);
+        assert_eq!(envelope["ok"], false);
+        assert_eq!(envelope["data"], serde_json::json!({ "reason": "not found" }));
+    }
+
+    fn ticking_module() -> Arc<jsonrpsee::RpcModule<()>> {
+        let mut module = jsonrpsee::RpcModule::new(());
+
+        module
+            .register_subscription(
+                "ticks",
+                "ticks_notif",
+                "ticks_unsub",
+                |_params, subscription, _ctx| async move {
+                    let Ok(subscription) = subscription.accept().await else {
+                        return;
+                    };
+
+                    loop {
+                        let message = jsonrpsee::SubscriptionMessage::from_json(&1).unwrap();
+                        if subscription.send(message).await.is_err() {
+                            break;
+                        }
+
+                        tokio::time::sleep(Duration::from_millis(5)).await;
+                    }
+                },
+            )
+            .unwrap();
+
+        Arc::new(module)
+    }
+
+    #[tokio::test]
+    async fn stream_is_torn_down_only_after_the_last_subscriber_leaves() {
+        let module = ticking_module();
+        let mqtt_options = rumqttc::MqttOptions::new("test", "127.0.0.1", 1883);
+        let (client, _event_loop) = AsyncClient::new(mqtt_options, 64);
+        let live_subscriptions: LiveSubscriptions = Default::default();
+
+        handle_subscribe(
+            module.clone(),
+            client.clone(),
+            "ticks".to_string(),
+            serde_json::to_vec(&serde_json::json!({ "client_id": "a" })).unwrap(),
+            live_subscriptions.clone(),
+        )
+        .await;
+
+        handle_subscribe(
+            module.clone(),
+            client.clone(),
+            "ticks".to_string(),
+            serde_json::to_vec(&serde_json::json!({ "client_id": "b" })).unwrap(),
+            live_subscriptions.clone(),
+        )
+        .await;
+
+        assert!(live_subscriptions.lock().await.contains_key("ticks"));
+
+        handle_unsubscribe(
+            "ticks".to_string(),
+            serde_json::to_vec(&serde_json::json!({ "client_id": "a" })).unwrap(),
+            live_subscriptions.clone(),
+        )
+        .await;
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(
+            live_subscriptions.lock().await.contains_key("ticks"),
+            "stream must keep running while a subscriber remains"
+        );
+
+        handle_unsubscribe(
+            "ticks".to_string(),
+            serde_json::to_vec(&serde_json::json!({ "client_id": "b" })).unwrap(),
+            live_subscriptions.clone(),
+        )
+        .await;
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(
+            !live_subscriptions.lock().await.contains_key("ticks"),
+            "stream must be torn down once the last subscriber leaves"
+        );
+    }
+}